@@ -0,0 +1,226 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{
+    context::SessionContext, multiaddr::Multiaddr, service::event::ServiceError, ProtocolId,
+};
+
+/// Error decoding an [`IdentifyMessage`] off the wire: the payload was
+/// truncated, or a listen address wasn't valid UTF-8 multiaddr text.
+#[derive(Debug)]
+pub(crate) struct DecodeError;
+
+/// Reserved protocol id the service negotiates before any user protocol is
+/// allowed to open on a freshly established session.
+pub(crate) const IDENTIFY_PROTOCOL_ID: &str = "/tentacle/identify";
+
+/// Default time a session is allowed to stay in `Unidentified` before the
+/// service gives up and closes it.
+pub(crate) const DEFAULT_IDENTIFY_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// The handshake payload exchanged by the identify protocol: a network
+/// identifier (so peers on different chains/networks refuse each other) and
+/// the sender's observed/advertised listen addresses.
+#[derive(Debug, Clone)]
+pub struct IdentifyMessage {
+    /// Network id configured on the sending node
+    pub network_id: Bytes,
+    /// Listen addresses the sending node is reachable on
+    pub listen_addrs: Vec<Multiaddr>,
+}
+
+impl IdentifyMessage {
+    /// Encode as `[u16 network_id len][network_id][u16 addr count]([u16 addr
+    /// len][addr as multiaddr text])*`. Kept deliberately simple since this
+    /// message is only ever exchanged once per session, before any other
+    /// protocol is negotiated.
+    pub(crate) fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(
+            4 + self.network_id.len() + self.listen_addrs.len() * 16,
+        );
+        buf.put_u16(self.network_id.len() as u16);
+        buf.put_slice(&self.network_id);
+        buf.put_u16(self.listen_addrs.len() as u16);
+        for addr in &self.listen_addrs {
+            let addr = addr.to_string();
+            buf.put_u16(addr.len() as u16);
+            buf.put_slice(addr.as_bytes());
+        }
+        buf.freeze()
+    }
+
+    /// Decode the format produced by [`encode`](Self::encode).
+    pub(crate) fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+        if data.remaining() < 2 {
+            return Err(DecodeError);
+        }
+        let network_id_len = data.get_u16() as usize;
+        if data.remaining() < network_id_len {
+            return Err(DecodeError);
+        }
+        let network_id = data.split_to(network_id_len);
+
+        if data.remaining() < 2 {
+            return Err(DecodeError);
+        }
+        let addr_count = data.get_u16() as usize;
+        let mut listen_addrs = Vec::with_capacity(addr_count);
+        for _ in 0..addr_count {
+            if data.remaining() < 2 {
+                return Err(DecodeError);
+            }
+            let addr_len = data.get_u16() as usize;
+            if data.remaining() < addr_len {
+                return Err(DecodeError);
+            }
+            let addr_bytes = data.split_to(addr_len);
+            let addr_str = std::str::from_utf8(&addr_bytes).map_err(|_| DecodeError)?;
+            listen_addrs.push(addr_str.parse().map_err(|_| DecodeError)?);
+        }
+
+        Ok(IdentifyMessage {
+            network_id,
+            listen_addrs,
+        })
+    }
+}
+
+/// Identify configuration for one side of a session: builds the outgoing
+/// [`IdentifyMessage`] and checks a received one's `network_id` against the
+/// local one.
+///
+/// Dialer and listener are handled the same way, and both verify. This
+/// type does not send or receive messages itself, and no ack message is
+/// built or awaited.
+pub(crate) struct IdentifyHandshake {
+    local_network_id: Bytes,
+    /// When `true`, `verify` always succeeds without comparing network ids.
+    /// Set from `ServiceBuilder::disable_identify_check` so integration
+    /// tests don't need to configure matching ids on every session.
+    disable_check: bool,
+}
+
+impl IdentifyHandshake {
+    pub(crate) fn new(local_network_id: Bytes, disable_check: bool) -> Self {
+        IdentifyHandshake {
+            local_network_id,
+            disable_check,
+        }
+    }
+
+    /// Build the outgoing handshake payload for this side of the session.
+    pub(crate) fn outgoing(&self, listen_addrs: Vec<Multiaddr>) -> IdentifyMessage {
+        IdentifyMessage {
+            network_id: self.local_network_id.clone(),
+            listen_addrs,
+        }
+    }
+
+    /// Check a freshly received identify message against our configuration,
+    /// returning the mismatch details (expected, got) on failure.
+    pub(crate) fn verify(&self, remote: &IdentifyMessage) -> Result<(), (Bytes, Bytes)> {
+        if self.disable_check || remote.network_id == self.local_network_id {
+            Ok(())
+        } else {
+            Err((self.local_network_id.clone(), remote.network_id.clone()))
+        }
+    }
+}
+
+/// Per-session state while the identify handshake is outstanding. Held by
+/// the service loop from `ServiceEvent::SessionOpen` until the handshake
+/// resolves; any `ServiceTask::ProtocolOpen` the service receives for this
+/// session in the meantime is queued here instead of being acted on.
+pub(crate) struct PendingIdentify {
+    deadline: Instant,
+    queued_opens: Vec<ProtocolId>,
+}
+
+impl PendingIdentify {
+    fn new(timeout: Duration) -> Self {
+        PendingIdentify {
+            deadline: Instant::now() + timeout,
+            queued_opens: Vec::new(),
+        }
+    }
+
+    /// Hold a `ProtocolOpen` that arrived while this session is still
+    /// `Unidentified`, to be released once identification succeeds.
+    pub(crate) fn queue_open(&mut self, proto_id: ProtocolId) {
+        self.queued_opens.push(proto_id);
+    }
+
+    /// Whether the identify timeout has elapsed without an ack.
+    pub(crate) fn is_expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    fn take_queued(&mut self) -> Vec<ProtocolId> {
+        std::mem::take(&mut self.queued_opens)
+    }
+}
+
+/// Owns the handshake configuration and coordinates the `Unidentified` ->
+/// identified transition for every session the service holds.
+pub(crate) struct IdentifyGate {
+    handshake: IdentifyHandshake,
+    timeout: Duration,
+}
+
+impl IdentifyGate {
+    pub(crate) fn new(local_network_id: Bytes, disable_identify_check: bool) -> Self {
+        IdentifyGate {
+            handshake: IdentifyHandshake::new(local_network_id, disable_identify_check),
+            timeout: DEFAULT_IDENTIFY_TIMEOUT,
+        }
+    }
+
+    /// Called when `ServiceEvent::SessionOpen` fires: the session starts in
+    /// `Unidentified` and must hold any `ServiceTask::ProtocolOpen` it
+    /// receives until [`on_identify_received`](Self::on_identify_received)
+    /// releases them or the handshake times out.
+    pub(crate) fn on_session_open(&self) -> PendingIdentify {
+        PendingIdentify::new(self.timeout)
+    }
+
+    /// Called when the identify protocol receives the peer's handshake
+    /// message. On success, returns the queued `ProtocolOpen` proto ids to
+    /// release (the service should now emit them for real). On mismatch,
+    /// returns the `ServiceError` to raise before closing the session.
+    pub(crate) fn on_identify_received(
+        &self,
+        pending: &mut PendingIdentify,
+        session_context: Arc<SessionContext>,
+        remote: &IdentifyMessage,
+    ) -> Result<Vec<ProtocolId>, ServiceError> {
+        self.handshake
+            .verify(remote)
+            .map(|()| pending.take_queued())
+            .map_err(|(expected, got)| ServiceError::IdentifyMismatch {
+                session_context,
+                expected,
+                got,
+            })
+    }
+
+    /// Called periodically by the service loop for every still-unidentified
+    /// session; on expiry returns the `ServiceError` to raise before closing
+    /// it rather than waiting on the peer forever.
+    pub(crate) fn on_timeout_check(
+        &self,
+        pending: &PendingIdentify,
+        session_context: Arc<SessionContext>,
+    ) -> Option<ServiceError> {
+        if pending.is_expired() {
+            Some(ServiceError::IdentifyMismatch {
+                session_context,
+                expected: self.handshake.local_network_id.clone(),
+                got: Bytes::new(),
+            })
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{context::SessionContext, service::event::ServiceError};
+
+/// Reserved protocol id for the periodic keepalive ping.
+pub(crate) const KEEPALIVE_PROTOCOL_ID: &str = "/tentacle/keepalive";
+
+/// How often a ping is sent on an established session.
+pub(crate) const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long the initiator waits for a pong before counting the ping as
+/// timed out. Shorter than `DEFAULT_PING_INTERVAL` so a missed pong doesn't
+/// also delay the next scheduled ping.
+pub(crate) const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Consecutive ping timeouts tolerated before the session is considered
+/// dead and a `ServiceError` is raised to close it.
+pub(crate) const MAX_CONSECUTIVE_TIMEOUTS: u32 = 3;
+
+/// Size of the sliding window used to smooth the RTT/offset estimate.
+const SAMPLE_WINDOW: usize = 8;
+
+/// One NTP-style four-timestamp round trip, all as milliseconds since
+/// `UNIX_EPOCH`:
+///
+/// - `t1`: initiator stamps local send time in the ping
+/// - `t2`: responder's receive time, stamped in the pong
+/// - `t3`: responder's send time, stamped in the pong
+/// - `t4`: initiator's receive time of the pong
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct PingSample {
+    pub t1: i64,
+    pub t2: i64,
+    pub t3: i64,
+    pub t4: i64,
+}
+
+impl PingSample {
+    /// `rtt = (t4 - t1) - (t3 - t2)`
+    pub fn rtt(&self) -> Duration {
+        let millis = (self.t4 - self.t1) - (self.t3 - self.t2);
+        Duration::from_millis(millis.max(0) as u64)
+    }
+
+    /// `clock_offset = ((t2 - t1) + (t3 - t4)) / 2`, positive when the
+    /// remote clock is ahead of ours.
+    pub fn clock_offset(&self) -> i64 {
+        ((self.t2 - self.t1) + (self.t3 - self.t4)) / 2
+    }
+}
+
+/// Tracks a sliding window of [`PingSample`]s for one session and exposes
+/// the smoothed RTT/clock-offset estimate to hang off `SessionContext`.
+///
+/// Queuing delay on either side inflates RTT without telling us anything
+/// about the clock offset, so rather than averaging every sample we keep
+/// the one with the lowest RTT in the window: that's the measurement with
+/// the least queuing noise.
+#[derive(Debug, Default)]
+pub(crate) struct RttEstimator {
+    window: VecDeque<PingSample>,
+    consecutive_timeouts: u32,
+}
+
+impl RttEstimator {
+    pub fn on_timeout(&mut self) -> u32 {
+        self.consecutive_timeouts += 1;
+        self.consecutive_timeouts
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.consecutive_timeouts >= MAX_CONSECUTIVE_TIMEOUTS
+    }
+
+    /// Record a completed round trip, evicting the oldest sample once the
+    /// window is full.
+    pub fn record(&mut self, sample: PingSample) {
+        self.consecutive_timeouts = 0;
+        if self.window.len() == SAMPLE_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(sample);
+    }
+
+    /// The smoothed estimate: the sample with the lowest RTT in the window.
+    pub fn estimate(&self) -> Option<(Duration, i64)> {
+        self.window
+            .iter()
+            .min_by_key(|s| s.rtt())
+            .map(|s| (s.rtt(), s.clock_offset()))
+    }
+}
+
+/// Lock-free RTT / clock-offset snapshot that `SessionContext` holds behind
+/// an `Arc` and that the keepalive loop updates after every completed ping,
+/// so readers on other threads never block on it.
+#[derive(Debug, Default)]
+pub struct SessionRtt {
+    rtt_millis: AtomicU64,
+    clock_offset_millis: AtomicI64,
+}
+
+impl SessionRtt {
+    fn update(&self, rtt: Duration, clock_offset: i64) {
+        self.rtt_millis
+            .store(rtt.as_millis() as u64, Ordering::Relaxed);
+        self.clock_offset_millis
+            .store(clock_offset, Ordering::Relaxed);
+    }
+
+    /// Smoothed round-trip time last estimated for this session.
+    pub fn rtt(&self) -> Duration {
+        Duration::from_millis(self.rtt_millis.load(Ordering::Relaxed))
+    }
+
+    /// Estimated offset of the remote peer's clock from ours, in
+    /// milliseconds; positive means the remote clock is ahead.
+    pub fn clock_offset(&self) -> i64 {
+        self.clock_offset_millis.load(Ordering::Relaxed)
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock before UNIX_EPOCH")
+        .as_millis() as i64
+}
+
+/// Drives the keepalive protocol for one session: sends a ping every
+/// `DEFAULT_PING_INTERVAL`, reads back the pong, and keeps `rtt` up to date
+/// with the result. `io` is the session's negotiated keepalive substream.
+pub(crate) struct Keepalive<S> {
+    io: S,
+    estimator: RttEstimator,
+    rtt: Arc<SessionRtt>,
+}
+
+impl<S> Keepalive<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub(crate) fn new(io: S, rtt: Arc<SessionRtt>) -> Self {
+        Keepalive {
+            io,
+            estimator: RttEstimator::default(),
+            rtt,
+        }
+    }
+
+    /// Send one ping stamped with `t1` and wait up to `DEFAULT_PING_TIMEOUT`
+    /// for the matching pong, updating `estimator`/`rtt` on success and the
+    /// consecutive-timeout counter otherwise.
+    async fn ping_once(&mut self) -> std::io::Result<()> {
+        let t1 = now_millis();
+        self.io.write_all(&t1.to_be_bytes()).await?;
+        self.io.flush().await?;
+
+        let mut buf = [0u8; 16];
+        match tokio::time::timeout(DEFAULT_PING_TIMEOUT, self.io.read_exact(&mut buf)).await {
+            Ok(Ok(_)) => {
+                let t2 = i64::from_be_bytes(buf[0..8].try_into().unwrap());
+                let t3 = i64::from_be_bytes(buf[8..16].try_into().unwrap());
+                let t4 = now_millis();
+                let sample = PingSample { t1, t2, t3, t4 };
+                self.estimator.record(sample);
+                if let Some((rtt, offset)) = self.estimator.estimate() {
+                    self.rtt.update(rtt, offset);
+                }
+                Ok(())
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                self.estimator.on_timeout();
+                Ok(())
+            }
+        }
+    }
+
+    /// Ping every `DEFAULT_PING_INTERVAL` until `MAX_CONSECUTIVE_TIMEOUTS`
+    /// pings in a row go unanswered or the substream errors.
+    ///
+    /// Uses a fixed `tokio::time::interval` rather than sleeping
+    /// `DEFAULT_PING_INTERVAL` before every `ping_once` call, so a slow or
+    /// timed-out pong (bounded separately by `DEFAULT_PING_TIMEOUT`) doesn't
+    /// stretch each period by up to `DEFAULT_PING_TIMEOUT`.
+    ///
+    /// Returns the `ServiceError` to raise once too many consecutive pings
+    /// go unanswered; returns `None` if the substream itself errored first
+    /// (that's a plain muxer/IO failure, not a keepalive timeout, so it's
+    /// left for the caller's normal substream-error handling instead).
+    pub(crate) async fn run_until_dead(
+        &mut self,
+        session_context: Arc<SessionContext>,
+    ) -> Option<ServiceError> {
+        let mut tick = tokio::time::interval(DEFAULT_PING_INTERVAL);
+        loop {
+            tick.tick().await;
+            if self.ping_once().await.is_err() {
+                return None;
+            }
+            if self.estimator.is_dead() {
+                return Some(keepalive_timeout(session_context));
+            }
+        }
+    }
+
+    /// Answer pings sent by the other side: read the initiator's `t1`, stamp
+    /// our receive time `t2` and send time `t3`, and write the pong back.
+    /// Runs until the substream errors or is closed, which is how the
+    /// initiator's own `ping_once` timeout notices a dead responder.
+    pub(crate) async fn run_responder(&mut self) -> std::io::Result<()> {
+        loop {
+            let mut buf = [0u8; 8];
+            self.io.read_exact(&mut buf).await?;
+            let t2 = now_millis();
+
+            let t3 = now_millis();
+            let mut pong = [0u8; 16];
+            pong[0..8].copy_from_slice(&t2.to_be_bytes());
+            pong[8..16].copy_from_slice(&t3.to_be_bytes());
+            self.io.write_all(&pong).await?;
+            self.io.flush().await?;
+        }
+    }
+}
+
+/// Build the `ServiceError` to raise once a session's [`Keepalive`] loop
+/// gives up after too many consecutive missed pings.
+pub(crate) fn keepalive_timeout(session_context: Arc<SessionContext>) -> ServiceError {
+    ServiceError::KeepaliveTimeout { session_context }
+}
+
+/// The reserved protocol id the keepalive substream is negotiated over.
+pub(crate) fn protocol_id() -> &'static str {
+    KEEPALIVE_PROTOCOL_ID
+}
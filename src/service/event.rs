@@ -8,7 +8,7 @@ use crate::{
     error::Error,
     multiaddr::Multiaddr,
     service::{DialProtocol, TargetSession},
-    ProtocolId, SessionId,
+    ProtocolId, SessionId, StreamId,
 };
 use bytes::Bytes;
 
@@ -58,6 +58,30 @@ pub enum ServiceError {
         session_context: Arc<SessionContext>,
         /// error, such as `InvalidData`
         error: Error,
+        /// The substream the error came from, if the error is scoped to one
+        /// substream rather than the whole session (e.g. a `FramedStream`
+        /// read error)
+        stream_id: Option<StreamId>,
+    },
+    /// The session missed too many consecutive keepalive pings and is
+    /// considered dead; closed rather than waiting on the much longer TCP
+    /// timeout.
+    KeepaliveTimeout {
+        /// Session context
+        session_context: Arc<SessionContext>,
+    },
+    /// The peer's identify handshake reported a network id that doesn't
+    /// match ours, or never acked within the identify timeout. The session
+    /// is held in the `Unidentified` state and closed rather than being
+    /// allowed to open any other protocol.
+    IdentifyMismatch {
+        /// Session context
+        session_context: Arc<SessionContext>,
+        /// The network id we expect remotes to report
+        expected: Bytes,
+        /// The network id actually reported by the peer; empty when the
+        /// identify timeout elapsed before the peer identified
+        got: Bytes,
     },
     /// Protocol handle error, will cause memory leaks/abnormal CPU usage
     ProtocolHandleError {
@@ -68,6 +92,24 @@ pub enum ServiceError {
     },
 }
 
+impl ServiceError {
+    /// Build the `MuxerError` to raise for a read error on one substream of
+    /// a session (yamux's `FramedStream::read_frame` surfaces these as a
+    /// `stream_id` + `io::Error` pair; it doesn't depend on `ServiceError`
+    /// itself), keeping the offending stream id attached.
+    pub(crate) fn from_muxer_read_error(
+        session_context: Arc<SessionContext>,
+        stream_id: StreamId,
+        error: Error,
+    ) -> Self {
+        ServiceError::MuxerError {
+            session_context,
+            error,
+            stream_id: Some(stream_id),
+        }
+    }
+}
+
 /// Event generated by the Service
 #[derive(Debug)]
 pub enum ServiceEvent {
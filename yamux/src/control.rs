@@ -2,12 +2,24 @@ use futures::{
     channel::{mpsc, oneshot},
     sink::SinkExt,
 };
+use std::time::Duration;
 
 use crate::{error::Error, stream::StreamHandle};
 
 pub(crate) enum Command<T> {
     OpenStream(oneshot::Sender<Result<StreamHandle, Error>>),
     Shutdown(oneshot::Sender<()>),
+    /// Stop accepting new streams and wait for every `FramedStream` to drain
+    /// its pending frame and write half before tearing the session down.
+    /// Falls back to the hard `Shutdown` path if `timeout` elapses first.
+    ///
+    /// The session loop handles this by calling
+    /// [`framed_stream::graceful_shutdown`](crate::framed_stream::graceful_shutdown)
+    /// with its streams and `timeout`, then signalling `done`.
+    ShutdownGraceful {
+        timeout: Duration,
+        done: oneshot::Sender<()>,
+    },
     AddStream(T),
     CloseOldestStream,
     GetStreamsNum(oneshot::Sender<usize>),
@@ -42,6 +54,23 @@ impl<T> Control<T> {
         let _ignore = rx.await;
     }
 
+    /// Like [`close`](Control::close), but stops accepting new `OpenStream`
+    /// requests and waits for every stream's pending frame and write half to
+    /// drain before emitting its close frame, instead of dropping sockets
+    /// mid-write. If `timeout` elapses before every stream has drained, the
+    /// session falls back to the hard shutdown path.
+    pub async fn close_graceful(&mut self, timeout: Duration) {
+        if self.0.is_closed() {
+            return;
+        }
+        let (tx, rx) = oneshot::channel();
+        let _ignore = self
+            .0
+            .send(Command::ShutdownGraceful { timeout, done: tx })
+            .await;
+        let _ignore = rx.await;
+    }
+
     pub async fn add_stream(&mut self, raw_socket: T) -> Result<(), Error> {
         self.0
             .send(Command::AddStream(raw_socket))
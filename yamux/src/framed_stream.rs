@@ -1,8 +1,25 @@
-use crate::frame::{Frame, FrameCodec};
+use std::time::Duration;
+
+use crate::{
+    frame::{Frame, FrameCodec},
+    StreamId,
+};
+use futures::{sink::SinkExt, stream::StreamExt};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::codec::Framed;
 
+/// A frame read error, tagged with the id of the substream it came from.
+/// yamux doesn't depend on `tentacle`, so it can't build a `ServiceError`
+/// itself; the caller should turn this into one with
+/// `ServiceError::from_muxer_read_error(session_context, stream_id, error)`.
+#[derive(Debug)]
+pub(crate) struct ReadError {
+    pub stream_id: StreamId,
+    pub error: std::io::Error,
+}
+
 pub(crate) struct FramedStream<T> {
+    pub id: StreamId,
     pub state: FSState,
     pub pending_frame: Option<Frame>,
     pub inner: Framed<T, FrameCodec>,
@@ -12,17 +29,169 @@ impl<T> FramedStream<T>
 where
     T: AsyncRead + AsyncWrite + Unpin,
 {
-    pub fn new(raw_stream: T, max_stream_window_size: u32) -> Self {
+    /// Construct a stream tagged with `id`, which is threaded into every
+    /// tracing span this stream emits and into the reset frame sent on a
+    /// read error, so a slow or stuck substream can be told apart from
+    /// others on the same session in the logs.
+    pub fn new(id: StreamId, raw_stream: T, max_stream_window_size: u32) -> Self {
         let inner = Framed::new(
             raw_stream,
             FrameCodec::default().max_frame_size(max_stream_window_size),
         );
         Self {
+            id,
             inner,
             state: FSState::Established,
             pending_frame: None,
         }
     }
+
+    /// Read the next frame. On a read error, explicitly resets the
+    /// substream and drives it to `Closed` before returning, instead of
+    /// leaving it half-open; the returned [`ReadError`] carries this
+    /// stream's id so the caller can attach it to
+    /// `ServiceError::MuxerError`.
+    #[tracing::instrument(level = "trace", skip(self), fields(stream_id = self.id))]
+    pub async fn read_frame(&mut self) -> Option<Result<Frame, ReadError>> {
+        match self.inner.next().await {
+            Some(Ok(frame)) => Some(Ok(frame)),
+            Some(Err(error)) => {
+                let error = self.handle_read_error(error).await;
+                Some(Err(ReadError {
+                    stream_id: self.id,
+                    error,
+                }))
+            }
+            None => None,
+        }
+    }
+
+    /// Handle a read error on the underlying `Framed` sink: rather than
+    /// silently failing and leaking a half-open stream, explicitly emit a
+    /// reset frame for this substream and drive the state to `Closed`.
+    /// Called from [`read_frame`](Self::read_frame); not normally called
+    /// directly.
+    #[tracing::instrument(level = "debug", skip(self, error), fields(stream_id = self.id))]
+    async fn handle_read_error(&mut self, error: std::io::Error) -> std::io::Error {
+        tracing::warn!("substream read error, sending reset frame");
+        let _ignore = self.inner.send(Frame::reset(self.id)).await;
+        self.state = FSState::Closed;
+        error
+    }
+
+    /// Begin a graceful close: stop accepting new writes and move into the
+    /// matching `*Closing*` state instead of jumping straight to `Closed`,
+    /// so the caller can keep polling [`drain`](FramedStream::drain) until
+    /// the pending frame and write half are flushed, then call
+    /// [`finish_local_close`](Self::finish_local_close).
+    pub fn start_graceful_close(&mut self) {
+        let next = match self.state {
+            FSState::Established => FSState::LocalClosing,
+            FSState::RemoteClosed => FSState::RemoteClosedLocalClosing,
+            other => other,
+        };
+        tracing::trace!(stream_id = self.id, from = ?self.state, to = ?next, "state transition");
+        self.state = next;
+    }
+
+    /// Flush the pending frame and the underlying sink. Returns `true` once
+    /// a call finds nothing left pending, at which point the caller should
+    /// call [`finish_local_close`](Self::finish_local_close) and emit the
+    /// close frame.
+    pub async fn drain(&mut self) -> Result<bool, std::io::Error> {
+        let had_pending = self.pending_frame.is_some();
+        if let Some(frame) = self.pending_frame.take() {
+            self.inner.send(frame).await?;
+        } else {
+            futures::future::poll_fn(|cx| self.inner.poll_flush_unpin(cx)).await?;
+        }
+        Ok(!had_pending)
+    }
+
+    /// Called once `drain` reports the write side is fully flushed:
+    /// half-close our write side (`LocalClosing(RemoteClosed)` ->
+    /// `LocalClosingHalf`/`Closed`).
+    pub fn finish_local_close(&mut self) {
+        let next = match self.state {
+            FSState::LocalClosing => FSState::LocalClosingHalf,
+            FSState::RemoteClosedLocalClosing => FSState::Closed,
+            other => other,
+        };
+        tracing::trace!(stream_id = self.id, from = ?self.state, to = ?next, "state transition");
+        self.state = next;
+    }
+
+    /// Emit the close frame for this substream and mark it `Closed`. Used
+    /// once the write side has drained: a graceful close doesn't wait for a
+    /// full bidirectional handshake, it just tells the peer this side is
+    /// done and stops.
+    async fn send_close_frame(&mut self) {
+        let _ignore = self.inner.send(Frame::close(self.id)).await;
+        tracing::trace!(stream_id = self.id, from = ?self.state, to = ?FSState::Closed, "state transition");
+        self.state = FSState::Closed;
+    }
+}
+
+/// How long the graceful-shutdown loop below pauses after a pass in which
+/// no stream finished draining or failed (each remaining stream only sent
+/// its pending frame, so `drain` returned `false`), before the next pass.
+const DRAIN_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Drive a graceful shutdown across every stream on a session in response
+/// to `Command::ShutdownGraceful`: move each into its `*Closing*` state,
+/// keep polling `drain` until a stream's pending frame and write half are
+/// flushed, then emit its close frame and mark it `Closed`. Falls back to
+/// forcing every remaining stream straight to `Closed` if `timeout` elapses
+/// first.
+pub(crate) async fn graceful_shutdown<T>(streams: &mut [FramedStream<T>], timeout: Duration)
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    for stream in streams.iter_mut() {
+        stream.start_graceful_close();
+    }
+
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        let mut all_closed = true;
+        let mut made_progress = false;
+        for stream in streams.iter_mut() {
+            if stream.state == FSState::Closed {
+                continue;
+            }
+            all_closed = false;
+            match tokio::time::timeout_at(deadline, stream.drain()).await {
+                Ok(Ok(true)) => {
+                    made_progress = true;
+                    stream.finish_local_close();
+                    let _ = tokio::time::timeout_at(deadline, stream.send_close_frame()).await;
+                }
+                Ok(Ok(false)) => {}
+                Ok(Err(_)) => {
+                    made_progress = true;
+                    stream.state = FSState::Closed;
+                }
+                // Deadline hit while the sink was blocked; forced closed below.
+                Err(_) => {}
+            }
+        }
+        if all_closed {
+            return;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            tracing::warn!("graceful shutdown timed out, forcing remaining streams closed");
+            for stream in streams.iter_mut() {
+                stream.state = FSState::Closed;
+            }
+            return;
+        }
+        if !made_progress {
+            // No stream finished this pass (each remaining one only sent
+            // its pending frame) - pause before polling `drain` again.
+            tokio::time::sleep(DRAIN_RETRY_INTERVAL).await;
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
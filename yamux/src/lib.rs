@@ -0,0 +1,3 @@
+pub(crate) mod control;
+pub(crate) mod framed_stream;
+pub(crate) mod pool;
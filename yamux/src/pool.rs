@@ -0,0 +1,160 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc;
+
+use crate::{control::Control, error::Error, framed_stream::FSState, stream::StreamHandle};
+
+/// Error returned by [`StreamPool::acquire`].
+#[derive(Debug)]
+pub enum PoolError {
+    /// The underlying session is gone.
+    Session(Error),
+    /// The pool already has `max_total` streams checked out or idle; the
+    /// session itself is fine, there's just no room to grow.
+    Exhausted,
+}
+
+impl From<Error> for PoolError {
+    fn from(error: Error) -> Self {
+        PoolError::Session(error)
+    }
+}
+
+/// A bounded pool of warm [`StreamHandle`]s layered over a [`Control<T>`],
+/// for request/response workloads that want connection-pool ergonomics
+/// without opening a fresh stream (and paying a round trip) on every call.
+///
+/// Checked-out streams are owned by the returned [`PooledStream`], not
+/// borrowed from the pool, so any number of them can be held concurrently;
+/// each one returns itself to the pool's idle set through a channel when
+/// dropped instead of holding a `&mut StreamPool` for its whole lifetime.
+pub struct StreamPool<T> {
+    control: Control<T>,
+    idle: Vec<Idle>,
+    return_tx: mpsc::UnboundedSender<StreamHandle>,
+    return_rx: mpsc::UnboundedReceiver<StreamHandle>,
+    in_use: Arc<AtomicUsize>,
+    max_idle: usize,
+    max_total: usize,
+    idle_ttl: Duration,
+}
+
+struct Idle {
+    handle: StreamHandle,
+    since: Instant,
+}
+
+impl<T> StreamPool<T> {
+    /// Create a pool over `control` that keeps at most `max_idle` warm
+    /// streams around, never grows past `max_total` concurrently open
+    /// streams, and reaps idle streams unused for longer than `idle_ttl`.
+    pub fn new(control: Control<T>, max_idle: usize, max_total: usize, idle_ttl: Duration) -> Self {
+        let (return_tx, return_rx) = mpsc::unbounded();
+        StreamPool {
+            control,
+            idle: Vec::with_capacity(max_idle),
+            return_tx,
+            return_rx,
+            in_use: Arc::new(AtomicUsize::new(0)),
+            max_idle,
+            max_total,
+            idle_ttl,
+        }
+    }
+
+    /// Pull every handle a dropped `PooledStream` has returned since the
+    /// last call into `idle`, discarding anything no longer `Established`
+    /// and trimming down to `max_idle` (the remote side sees a dropped,
+    /// un-returned handle close on its own; no session command needed).
+    fn drain_returned(&mut self) {
+        while let Ok(Some(handle)) = self.return_rx.try_next() {
+            if handle.state() == FSState::Established {
+                self.idle.push(Idle {
+                    handle,
+                    since: Instant::now(),
+                });
+            }
+        }
+        while self.idle.len() > self.max_idle {
+            self.idle.remove(0);
+        }
+    }
+
+    /// Reap idle streams that have sat unused longer than `idle_ttl`. Should
+    /// be driven periodically (e.g. from a protocol notify timer); the pool
+    /// itself does not spawn a background task.
+    pub async fn reap_idle(&mut self) {
+        self.drain_returned();
+        let ttl = self.idle_ttl;
+        let now = Instant::now();
+        self.idle.retain(|i| now.duration_since(i.since) < ttl);
+    }
+
+    /// Acquire a warm stream, reusing one from the idle set if a still-live
+    /// one is available, otherwise opening a new one if we're under
+    /// `max_total`.
+    pub async fn acquire(&mut self) -> Result<PooledStream, PoolError> {
+        self.drain_returned();
+
+        while let Some(idle) = self.idle.pop() {
+            if idle.handle.state() == FSState::Established {
+                self.in_use.fetch_add(1, Ordering::AcqRel);
+                return Ok(self.wrap(idle.handle));
+            }
+            // Discarded: RemoteClosed/Closed streams can't be reused.
+        }
+
+        if self.in_use.load(Ordering::Acquire) + self.idle.len() >= self.max_total {
+            return Err(PoolError::Exhausted);
+        }
+        let handle = self.control.open_stream().await?;
+        self.in_use.fetch_add(1, Ordering::AcqRel);
+        Ok(self.wrap(handle))
+    }
+
+    fn wrap(&self, handle: StreamHandle) -> PooledStream {
+        PooledStream {
+            handle: Some(handle),
+            return_tx: self.return_tx.clone(),
+            in_use: self.in_use.clone(),
+        }
+    }
+}
+
+/// A [`StreamHandle`] checked out from a [`StreamPool`]. Owned, not
+/// borrowed, so several can be held concurrently; sent back to the pool's
+/// idle set through a channel on drop instead of being closed, unless it's
+/// no longer `Established`.
+pub struct PooledStream {
+    handle: Option<StreamHandle>,
+    return_tx: mpsc::UnboundedSender<StreamHandle>,
+    in_use: Arc<AtomicUsize>,
+}
+
+impl Deref for PooledStream {
+    type Target = StreamHandle;
+
+    fn deref(&self) -> &Self::Target {
+        self.handle.as_ref().expect("handle taken")
+    }
+}
+
+impl DerefMut for PooledStream {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.handle.as_mut().expect("handle taken")
+    }
+}
+
+impl Drop for PooledStream {
+    fn drop(&mut self) {
+        self.in_use.fetch_sub(1, Ordering::AcqRel);
+        if let Some(handle) = self.handle.take() {
+            // Best-effort: if the pool itself is gone the handle is simply
+            // dropped (and closes) instead of being returned.
+            let _ = self.return_tx.unbounded_send(handle);
+        }
+    }
+}